@@ -1,3 +1,9 @@
+#![feature(allocator_api, ptr_metadata, layout_for_ptr)]
+// This binary is building up a small library's worth of heap primitives incrementally; `main`
+// only exercises a slice of the surface at any given point, and the rest is covered by tests
+// instead, so the usual "never constructed/called" signal isn't meaningful here.
+#![allow(dead_code)]
+
 #[derive(Clone, Copy)]
 #[repr(C)]
 pub union FieldValue {
@@ -18,114 +24,184 @@ impl Default for FieldValue {
 }
 
 use std::{
-    alloc::{Layout, LayoutError},
+    alloc::{AllocError, Allocator, Global, Layout},
+    any::TypeId,
     marker::PhantomData,
     ptr::NonNull,
 };
 
-/// Layout information for the layout
-struct InstanceLayoutInfo<T> {
-    /// The final layout
-    layout: Layout,
-    id_offset: usize,
-    length_offset: usize,
-    array_start_offset: usize,
-    _marker: PhantomData<*const T>,
-}
-impl<T: Sized> InstanceLayoutInfo<T> {
-    fn new(length: u16) -> Result<InstanceLayoutInfo<T>, LayoutError> {
-        // Based on Layout::extend example in docs for getting fields for a #[repr(C)] structure
-
-        let layout = BASE_LAYOUT?;
-
-        // Add the id
-        let (layout, id_offset) = layout.extend(ID_LAYOUT?)?;
-
-        // Add the length
-        let (layout, length_offset) = layout.extend(LENGTH_LAYOUT?)?;
-
-        // UCG: The layout of a slice [T] of length N is the same as that of a [T; N] array.
-        // and the docs for this says it is a record for [T; N]
-        // thus we could treat this as a [T]
-        let arrau_layout = Layout::array::<T>(usize::from(length)).unwrap();
-
-        let (layout, array_start_offset) = layout.extend(arrau_layout)?;
-
-        // TODO: Do we really need to do this? We aren't actually treating it as a C structure
-        // currently, just as structure that provides accessors to pointer data.
-        // We also aren't storing these directly sequentially in an array due their dynamic size
-        let layout = layout.pad_to_align();
+/// Marker for types with no uninitialized bytes: every byte of every value is determined (no
+/// padding, no unions with differently-sized variants left implicit). Required to view a `&T`
+/// as `&[u8]` without ever exposing uninitialized memory.
+///
+/// # Safety
+/// Implementors must guarantee that `size_of::<Self>()` bytes are always fully initialized for
+/// any live value of the type.
+pub unsafe trait NoUninit {}
+
+/// Marker for types where every bit pattern of `size_of::<Self>()` bytes is a valid value. This
+/// is what makes it sound to reinterpret an arbitrary `&[u8]` as `&Self` (e.g. when reading a
+/// buffer off disk or a socket) without validating its contents first.
+///
+/// # Safety
+/// Implementors must guarantee that every possible bit pattern is a valid value of the type.
+pub unsafe trait AnyBitPattern {}
+
+/// Marker for types with alignment 1, so reinterpreting a byte buffer as `&Self` never requires
+/// the buffer to be aligned.
+///
+/// # Safety
+/// Implementors must guarantee `align_of::<Self>() == 1`.
+pub unsafe trait Unaligned {}
+
+// Safety: `FieldValue` is a union of primitive, `Copy` fields; it is always fully initialized
+// (there's no padding for a union's bytes to leave uninitialized) and every bit pattern is a
+// valid value of at least one of its variants.
+unsafe impl NoUninit for FieldValue {}
+unsafe impl AnyBitPattern for FieldValue {}
+
+/// Declares an explicit-endianness wrapper around an integer, stored as a byte array (so it's
+/// `Unaligned`) rather than the native integer type. This is what makes it sound to read/write
+/// these as raw bytes regardless of the host's native endianness or the buffer's alignment.
+macro_rules! endian_integer {
+    ($name:ident, $int:ty, $to_bytes:ident, $from_bytes:ident) => {
+        #[derive(Clone, Copy)]
+        #[repr(transparent)]
+        pub struct $name([u8; std::mem::size_of::<$int>()]);
+        impl $name {
+            pub fn new(value: $int) -> Self {
+                $name(value.$to_bytes())
+            }
 
-        Ok(InstanceLayoutInfo {
-            layout,
-            id_offset,
-            length_offset,
-            array_start_offset,
-            _marker: PhantomData,
-        })
-    }
+            pub fn get(self) -> $int {
+                <$int>::$from_bytes(self.0)
+            }
+        }
+        // Safety: a plain byte array has no padding and every bit pattern is valid
+        unsafe impl NoUninit for $name {}
+        unsafe impl AnyBitPattern for $name {}
+        // Safety: stored as `[u8; N]`, which has alignment 1
+        unsafe impl Unaligned for $name {}
+    };
 }
 
-// Can't unwrap in a constant?
-const BASE_LAYOUT: Result<Layout, LayoutError> = Layout::from_size_align(0, 1);
-
-const ID_LAYOUT: Result<Layout, LayoutError> =
-    Layout::from_size_align(std::mem::size_of::<u32>(), std::mem::size_of::<u32>());
+endian_integer!(U16Be, u16, to_be_bytes, from_be_bytes);
+endian_integer!(U16Le, u16, to_le_bytes, from_le_bytes);
+endian_integer!(U32Be, u32, to_be_bytes, from_be_bytes);
+endian_integer!(U32Le, u32, to_le_bytes, from_le_bytes);
 
-const LENGTH_LAYOUT: Result<Layout, LayoutError> =
-    Layout::from_size_align(std::mem::size_of::<u16>(), std::mem::align_of::<u16>());
-
-// These functions should produce the same output as DstLayoutInfo would for their values
-
-/// Compute the layout of the struct up to id, returning its offset and the layout
-fn compute_id_layout_part() -> Result<(Layout, usize), LayoutError> {
-    let layout = BASE_LAYOUT?;
-    layout.extend(ID_LAYOUT?)
+/// The sized prefix of [`Dst`], used to compute the header's own size/alignment (via
+/// `Layout::new`) independently of any particular `T`. Keeping this as a real struct, rather than
+/// chaining `Layout::extend` calls by hand for each field, means the layout computation can never
+/// drift out of sync with `Dst`'s actual field list.
+#[repr(C)]
+struct DstHeader {
+    id: u32,
+    length: u16,
+    /// Explicit, always-initialized padding out to an 8-byte header size. Without this, a `T`
+    /// with alignment 8 (e.g. [`FieldValue`]) would force the compiler to insert *implicit*
+    /// padding here instead, which [`OwnedInstanceRef::as_bytes`] could never soundly expose as
+    /// `&[u8]`. Reserving it explicitly makes it a meaningful, zero-initialized byte instead.
+    _reserved: u16,
 }
 
-/// Compute the layout of the struct up to length, returning its offset and the layout
-fn compute_length_layout_part() -> Result<(Layout, usize), LayoutError> {
-    let layout = BASE_LAYOUT?;
-    let (layout, _id_offset) = layout.extend(ID_LAYOUT?)?;
-    layout.extend(LENGTH_LAYOUT?)
+/// The real, unsized layout of an instance: a small header followed by a trailing array whose
+/// length is carried as the pointer's metadata, rather than recomputed from a stored offset.
+///
+/// This is an ordinary (if unsized) `#[repr(C)]` struct, so `id`/`length`/`data` are at whatever
+/// offsets the compiler assigns them, and the usual field/index projections on a pointer to it
+/// are just that: no hand-rolled offset arithmetic required.
+#[repr(C)]
+struct Dst<T> {
+    id: u32,
+    length: u16,
+    _reserved: u16,
+    data: [T],
+}
+impl<T> Dst<T> {
+    /// The `Layout` of a `Dst<T>` with `length` trailing elements, computed purely from a fat
+    /// pointer's metadata.
+    ///
+    /// Safety: `length` must not make the struct's size overflow `isize`.
+    unsafe fn layout_for(length: u16) -> Layout {
+        // A fat pointer only needs a valid *metadata* (the slice length) to have its layout
+        // queried; the data pointer itself is never dereferenced here.
+        let fat: *const Dst<T> =
+            std::ptr::from_raw_parts(NonNull::<()>::dangling().as_ptr(), usize::from(length));
+
+        // Safety: caller upholds that this doesn't overflow isize; for_value_raw only inspects
+        // the pointer's metadata for a repr(C) struct whose only unsized field is a slice.
+        unsafe { Layout::for_value_raw(fat) }
+    }
 }
 
-/// Compute the layout of the struct up to array, returning its offset and the layout
-fn compute_array_layout_part<T>(length: u16) -> Result<(Layout, usize), LayoutError> {
-    let (layout, _length_offset) = compute_length_layout_part()?;
-    let arr_layout = Layout::array::<T>(usize::from(length)).unwrap();
+/// Scope-guard for the in-progress fill loop in [`OwnedInstanceRef::new_in`].
+///
+/// If `default_elem_func` (or anything else) panics partway through filling the array, this
+/// guard's `Drop` runs instead of `OwnedInstanceRef`'s, since the latter was never constructed.
+/// It drops exactly the `init_count` elements that were actually written and deallocates the
+/// backing buffer, so a panic mid-construction neither leaks the allocation nor leaks/double-drops
+/// the already-written elements.
+struct PartialInitGuard<'a, T, A: Allocator> {
+    ptr: NonNull<Dst<T>>,
+    init_count: u16,
+    layout: Layout,
+    alloc: &'a A,
+}
+impl<'a, T: Sized, A: Allocator> Drop for PartialInitGuard<'a, T, A> {
+    fn drop(&mut self) {
+        // Safety: offset was given by layout, and so should be in bounds of the allocation
+        let data_ptr = unsafe { std::ptr::addr_of_mut!((*self.ptr.as_ptr()).data) as *mut T };
+        for i in 0..self.init_count {
+            // Safety: indices below `init_count` were written by the caller before bumping it
+            let elem_ptr = unsafe { data_ptr.add(usize::from(i)) };
+            let value = unsafe { std::ptr::read(elem_ptr) };
+            drop(value);
+        }
 
-    layout.extend(arr_layout)
+        // Safety: ptr/layout came from a matching `self.alloc.allocate(layout)` and this guard
+        // only ever deallocates once
+        unsafe { self.alloc.deallocate(self.ptr.cast::<u8>(), self.layout) };
+    }
 }
 
-/// We can't turn a pointer of bytes into a fat pointer
-/// So we can't 'simply' return `*mut Dst` from `make_dst`
-/// Thus, we store it in a structure as the opaque pointer, which
-/// we must assume to be initialized.
-struct OwnedInstanceRef<T> {
-    ptr: NonNull<u8>,
-    // TODO: Is this correct?
-    _marker: PhantomData<*const T>,
+/// An owned, heap-allocated `Dst<T>`: header fields `id`/`length` plus a trailing array of `T`
+/// whose length is carried in the pointer's own metadata.
+struct OwnedInstanceRef<T, A: Allocator = Global> {
+    ptr: NonNull<Dst<T>>,
+    /// How many elements of the array (counting from the start) are actually initialized.
+    /// Equal to `length()` once construction via [`OwnedInstanceRef::new`] completes, but may be
+    /// less than `length()` for an instance built up via [`OwnedInstanceRef::new_uninit`] and
+    /// [`OwnedInstanceRef::push`].
+    init_len: u16,
+    alloc: A,
+    _marker: PhantomData<T>,
 }
-impl<T: Sized> OwnedInstanceRef<T> {
-    // Makes approximately
-    // #[repr(C)]
-    // struct Dst {
-    //    id: u32,
-    //    length: u16,
-    //    data: [FieldValue],
-    // }
-    // Though we can't literally use the struct definition because we can't construct the fat
-    // pointer for it. I think.
-    // Thus we simply allocate the data in that manner, making an opaque wrapper structure
-    // around a ptr.
-
-    // Most of the asserts in this are optimized out
+impl<T: Sized> OwnedInstanceRef<T, Global> {
     pub fn new(
         id: u32,
         length: u16,
         default_elem_func: impl Fn(usize) -> T,
-    ) -> Result<OwnedInstanceRef<T>, LayoutError> {
+    ) -> Result<OwnedInstanceRef<T, Global>, AllocError> {
+        Self::new_in(id, length, default_elem_func, Global)
+    }
+
+    /// See [`OwnedInstanceRef::new_uninit_in`].
+    pub fn new_uninit(id: u32, length: u16) -> Result<OwnedInstanceRef<T, Global>, AllocError> {
+        Self::new_uninit_in(id, length, Global)
+    }
+}
+impl<T: Sized, A: Allocator> OwnedInstanceRef<T, A> {
+    /// Allocate the backing buffer (via `alloc`) and write the `id`/`length` header, but leave
+    /// the array uninitialized. Returns the allocation's fat pointer and layout so callers can
+    /// finish initializing (or guard) the array themselves.
+    ///
+    /// Most of the asserts in this are optimized out
+    fn alloc_with_header(
+        alloc: &A,
+        id: u32,
+        length: u16,
+    ) -> Result<(NonNull<Dst<T>>, Layout), AllocError> {
         assert!(
             isize::try_from(length).is_ok(),
             "Failed to fit length into isize"
@@ -141,265 +217,877 @@ impl<T: Sized> OwnedInstanceRef<T> {
             "Overflowed isize with the number of elements"
         );
 
-        let InstanceLayoutInfo {
-            layout,
-            id_offset,
-            length_offset,
-            array_start_offset,
-            ..
-        } = InstanceLayoutInfo::<T>::new(length)?;
+        // Safety: the asserts above ensure the struct's size doesn't overflow isize
+        let layout = unsafe { Dst::<T>::layout_for(length) };
 
         // Allocate the data
+        let thin: NonNull<u8> = alloc.allocate(layout)?.cast::<u8>();
+        let ptr: NonNull<Dst<T>> =
+            NonNull::new(std::ptr::from_raw_parts_mut(thin.as_ptr() as *mut (), usize::from(length)))
+                .unwrap();
+
+        // Set id/length/reserved: ordinary field projections now that we have a real fat
+        // pointer, rather than offsets recomputed by hand.
+        // Safety: `ptr` was just allocated with the layout of a `Dst<T>` of this `length`
+        unsafe {
+            std::ptr::addr_of_mut!((*ptr.as_ptr()).id).write(id);
+            std::ptr::addr_of_mut!((*ptr.as_ptr()).length).write(length);
+            std::ptr::addr_of_mut!((*ptr.as_ptr())._reserved).write(0);
+        }
 
-        let ptr: *mut u8 = unsafe { std::alloc::alloc(layout) };
-        assert!(!ptr.is_null(), "Failed to allocate pointer");
-
-        // Set id
-        {
-            // I imagine layout should return valid offsets anyway
-            assert!(
-                isize::try_from(id_offset).is_ok(),
-                "Id offset overflows isize"
-            );
-            // Safety:
-            // - offset was given by layout, and so should be in bounds of the allocation
-            // - offset will not overflow an isize
-            let id_ptr: *mut u8 = unsafe { ptr.add(id_offset) };
-            let id_ptr: *mut u32 = id_ptr.cast::<u32>();
-            unsafe { std::ptr::write(id_ptr, id) };
-        };
+        Ok((ptr, layout))
+    }
 
-        // Set length
-        {
-            assert!(
-                isize::try_from(length_offset).is_ok(),
-                "Length offset overflows isize"
-            );
-
-            // Safety:
-            // - offset was given by layout, and so should be in bounds of the allocation
-            // - offset will not overflow an isize
-            let length_ptr: *mut u8 = unsafe { ptr.add(length_offset) };
-            let length_ptr: *mut u16 = length_ptr.cast::<u16>();
-            unsafe { std::ptr::write(length_ptr, length) };
+    pub fn new_in(
+        id: u32,
+        length: u16,
+        default_elem_func: impl Fn(usize) -> T,
+        alloc: A,
+    ) -> Result<OwnedInstanceRef<T, A>, AllocError> {
+        let (ptr, layout) = Self::alloc_with_header(&alloc, id, length)?;
+
+        // Guard the in-progress fill: if `default_elem_func` panics on element `k`, this guard's
+        // `Drop` runs (since we haven't returned an `OwnedInstanceRef` yet) and drops exactly the
+        // `k` already-written elements before deallocating, instead of leaking them and the
+        // allocation.
+        let mut guard = PartialInitGuard::<T, A> {
+            ptr,
+            init_count: 0,
+            layout,
+            alloc: &alloc,
         };
 
-        // Set values
-        {
-            assert!(
-                isize::try_from(array_start_offset).is_ok(),
-                "Array data start offset overflows isize"
-            );
-
-            // Safety:
-            // - offset was given by layout, and so should be in bounds of the allocation
-            // - offset will not overflow an isize
-            let arr_start_ptr: *mut u8 = unsafe { ptr.add(array_start_offset) };
-            let arr_start_ptr: *mut T = arr_start_ptr.cast::<T>();
-
-            assert!(
-                isize::try_from(length).is_ok(),
-                "Length would overflow isize"
-            );
-            for i in 0..length {
-                // Safety:
-                // - index should be valid since we told the layout to allocate an array of the
-                // length
-                // - index should also not overflow an isize, since length did not overflow an
-                // isize
-                let arr_element_ptr = unsafe { arr_start_ptr.add(usize::from(i)) };
-                let value = default_elem_func(usize::from(i));
-                unsafe {
-                    std::ptr::write(arr_element_ptr, value);
-                }
+        // Safety: `ptr` was allocated for exactly `length` trailing elements
+        let data_ptr = unsafe { std::ptr::addr_of_mut!((*ptr.as_ptr()).data) as *mut T };
+
+        for i in 0..length {
+            // Safety: index is within the `length`-element array this pointer was allocated for
+            let arr_element_ptr = unsafe { data_ptr.add(usize::from(i)) };
+            let value = default_elem_func(usize::from(i));
+            unsafe {
+                std::ptr::write(arr_element_ptr, value);
             }
+            guard.init_count += 1;
         }
 
-        // Shouldn't panic because we already checked that it is non-null
-        let ptr = NonNull::new(ptr).unwrap();
+        // All elements are initialized: disarm the guard so it doesn't drop/deallocate the data
+        // we're about to hand off.
+        std::mem::forget(guard);
 
         // Safety: We've initialized all the fields to valid values
         Ok(OwnedInstanceRef {
             ptr,
+            init_len: length,
+            alloc,
             _marker: PhantomData,
         })
     }
 
-    pub fn id(&self) -> u32 {
-        // Should not panic since we had to do the same logic to construct this instance
-        // in the first place
-        let (_id_layout, id_offset) = compute_id_layout_part().unwrap();
-        // Safety: The construction of the structure should only have been done through
-        // the `new` function which ensures this is a valid pointer and holds initialized
-        // memory.
-        let id_ptr: *const u8 = unsafe { self.ptr.as_ptr().add(id_offset) };
-        let id_ptr: *const u32 = id_ptr.cast::<u32>();
+    /// Allocate an instance with capacity for `length` elements (using `alloc`), but without
+    /// initializing any of them. Callers build it up field-by-field with [`Self::push`];
+    /// `as_slice`/`get`/etc. only ever expose the initialized prefix `0..init_len()`, so the
+    /// uninitialized tail is never observable.
+    pub fn new_uninit_in(id: u32, length: u16, alloc: A) -> Result<OwnedInstanceRef<T, A>, AllocError> {
+        let (ptr, _layout) = Self::alloc_with_header(&alloc, id, length)?;
 
-        unsafe { std::ptr::read(id_ptr) }
+        Ok(OwnedInstanceRef {
+            ptr,
+            init_len: 0,
+            alloc,
+            _marker: PhantomData,
+        })
     }
 
-    pub fn length(&self) -> u16 {
-        // Should not panic since we had to do the same logic to construct this instance
-        // in the first place
-        let (_length_layout, length_offset) = compute_length_layout_part().unwrap();
-        // Safety: The construction of the structure should only have been done through
-        // the `new` function which ensures this is a valid pointer and holds initialized
-        // memory.
+    /// Write `value` into the next uninitialized slot and advance `init_len`.
+    ///
+    /// Returns `Err(value)` without modifying `self` if the instance is already fully
+    /// initialized (`init_len() == length()`).
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.init_len >= self.length() {
+            return Err(value);
+        }
 
-        let length_ptr: *const u8 = unsafe { self.ptr.as_ptr().add(length_offset) };
-        let length_ptr: *const u16 = length_ptr.cast::<u16>();
+        // Safety: init_len < length, so this index is within the allocated array and is the
+        // first uninitialized slot
+        let data_ptr = unsafe { std::ptr::addr_of_mut!((*self.ptr.as_ptr()).data) as *mut T };
+        let elem_ptr = unsafe { data_ptr.add(usize::from(self.init_len)) };
+        unsafe { std::ptr::write(elem_ptr, value) };
 
-        unsafe { std::ptr::read(length_ptr) }
+        self.init_len += 1;
+
+        Ok(())
     }
 
-    pub fn as_slice(&self) -> &[T] {
-        let length = self.length();
+    /// How many elements of the array are currently initialized. See [`Self::length`] for the
+    /// total allocated capacity.
+    pub fn init_len(&self) -> u16 {
+        self.init_len
+    }
 
-        // Should not panic since we had to do the same logic to construct this instance
-        // in the first place
-        let (_array_layout, array_start_offset) =
-            compute_array_layout_part::<FieldValue>(length).unwrap();
+    pub fn id(&self) -> u32 {
+        // Safety: the construction of the structure should only have been done through `new_in`
+        // or `new_uninit_in`, which ensure this is a valid pointer and that `id` is initialized.
+        unsafe { std::ptr::addr_of!((*self.ptr.as_ptr()).id).read() }
+    }
 
-        let array_start_ptr: *const u8 = unsafe { self.ptr.as_ptr().add(array_start_offset) };
-        let array_start_ptr: *const T = array_start_ptr.cast::<T>();
+    pub fn length(&self) -> u16 {
+        // Safety: the construction of the structure should only have been done through `new_in`
+        // or `new_uninit_in`, which ensure this is a valid pointer and that `length` is
+        // initialized.
+        unsafe { std::ptr::addr_of!((*self.ptr.as_ptr()).length).read() }
+    }
 
-        let length = usize::from(length);
+    pub fn as_slice(&self) -> &[T] {
+        let init_len = usize::from(self.init_len);
 
         // Safety:
-        // - Data is initialized for length reads
+        // - The initialized prefix is initialized for init_len reads
         // - Should be aligned due to layout
         // - The backing array won't be mutated because the pointer is only accessed through the
         // reference and so the borrow checker will stop it from calling mutating methods
-        unsafe { std::slice::from_raw_parts(array_start_ptr, length) }
+        unsafe {
+            let data_ptr = std::ptr::addr_of!((*self.ptr.as_ptr()).data) as *const T;
+            std::slice::from_raw_parts(data_ptr, init_len)
+        }
     }
 
     pub fn as_mut_slice(&mut self) -> &mut [T] {
-        let length = self.length();
-
-        // Should not panic since we had to do the same logic to construct this instance
-        // in the first place
-        let (_array_layout, array_start_offset) = compute_array_layout_part::<T>(length).unwrap();
-
-        let array_start_ptr: *mut u8 = unsafe { self.ptr.as_ptr().add(array_start_offset) };
-        let array_start_ptr: *mut T = array_start_ptr.cast::<T>();
-
-        let length = usize::from(length);
+        let init_len = usize::from(self.init_len);
 
         // Safety:
-        // - Data is initialized for length reads
+        // - The initialized prefix is initialized for init_len reads
         // - Should be aligned due to layout
         // - The backing array won't be mutated because the pointer is only accessed through the
         // reference and so the borrow checker will stop it from calling other methods
-        unsafe { std::slice::from_raw_parts_mut(array_start_ptr, length) }
+        unsafe {
+            let data_ptr = std::ptr::addr_of_mut!((*self.ptr.as_ptr()).data) as *mut T;
+            std::slice::from_raw_parts_mut(data_ptr, init_len)
+        }
     }
 
     pub fn get(&self, i: u16) -> Option<&T> {
-        // We need the length to compute the proper layout and check validity
-        let length = self.length();
+        // Only the initialized prefix is valid to read
+        if i >= self.init_len {
+            // Index was out of bounds (or not yet initialized)
+            return None;
+        }
+
+        // Safety: i must be valid since the constructor assured that all indices up to length
+        // are valid, and we have asserted that `i < init_len`
+        let element = unsafe {
+            let data_ptr = std::ptr::addr_of!((*self.ptr.as_ptr()).data) as *const T;
+            &*data_ptr.add(usize::from(i))
+        };
+        Some(element)
+    }
 
-        if i >= length {
-            // Index was out of bounds
+    pub fn get_mut(&mut self, i: u16) -> Option<&mut T> {
+        // Only the initialized prefix is valid to read
+        if i >= self.init_len {
+            // Index was out of bounds (or not yet initialized)
             return None;
         }
 
-        // From here on out, the index is valid
+        // Safety: We are a mutable reference and so we are uniquely referenced and so we can
+        // return a unique reference to the data inside us.
+        let element = unsafe {
+            let data_ptr = std::ptr::addr_of_mut!((*self.ptr.as_ptr()).data) as *mut T;
+            &mut *data_ptr.add(usize::from(i))
+        };
+        Some(element)
+    }
+
+    /// Whether `Dst<T>`'s `#[repr(C)]` layout has to insert *implicit* padding between
+    /// [`DstHeader`] and the trailing `data` array, to satisfy `T`'s alignment, beyond the
+    /// `_reserved` padding `DstHeader` already accounts for explicitly. If it does, the
+    /// allocation as a whole isn't a flat run of meaningful bytes, so `as_bytes`/`as_bytes_mut`
+    /// refuse to hand it out.
+    ///
+    /// `DstHeader` is sized/aligned so this is `false` for any `T` with alignment up to 8 (in
+    /// particular [`FieldValue`] and the endian wrappers such as [`U16Be`], the [`NoUninit`]
+    /// types in this crate); it's only `true` for a `T` with a stricter alignment requirement.
+    fn has_header_padding() -> bool {
+        let header = Layout::new::<DstHeader>();
+        let (_with_data, data_offset) = header.extend(Layout::new::<T>()).unwrap();
+        data_offset != header.size()
+    }
 
-        // Should not panic since we had to do the same logic to construct this instance
-        // in the first place
-        let (_array_layout, array_start_offset) = compute_array_layout_part::<T>(length).unwrap();
+    /// A zero-copy view of this instance's whole allocation (header plus every element) as raw
+    /// bytes, suitable for writing to disk or a socket.
+    ///
+    /// Returns `None` if the instance isn't fully initialized yet (see
+    /// [`Self::init_len`]/[`Self::push`]), or if `T`'s alignment would force padding between the
+    /// header and the array that would otherwise be exposed as uninitialized bytes.
+    pub fn as_bytes(&self) -> Option<&[u8]>
+    where
+        T: NoUninit,
+    {
+        if self.init_len != self.length() || Self::has_header_padding() {
+            return None;
+        }
 
-        let array_start_ptr: *const u8 = unsafe { self.ptr.as_ptr().add(array_start_offset) };
-        let array_start_ptr: *const T = array_start_ptr.cast::<T>();
+        // Safety:
+        // - `T: NoUninit` and the padding check above mean every byte of the allocation,
+        // header included, is a meaningfully-initialized byte
+        // - `init_len == length`, so the trailing array is fully initialized too
+        // - the allocation is exactly `layout.size()` bytes, by construction
+        let layout = unsafe { Dst::<T>::layout_for(self.length()) };
+        Some(unsafe { std::slice::from_raw_parts(self.ptr.as_ptr() as *const u8, layout.size()) })
+    }
 
-        // Safety: i must be valid since the constructor assured that all indices up to length
-        // are valid, and we have asserted that `i < length`
-        let array_element_ptr: *const T = unsafe { array_start_ptr.add(usize::from(i)) };
+    /// Mutable counterpart to [`Self::as_bytes`]. Writing through this view mutates the live
+    /// `id`/`length`/elements in place, so callers must preserve whatever invariants `T` needs.
+    pub fn as_bytes_mut(&mut self) -> Option<&mut [u8]>
+    where
+        T: NoUninit,
+    {
+        if self.init_len != self.length() || Self::has_header_padding() {
+            return None;
+        }
 
-        // Safety: We are in a reference to the data, and so we can convert the ptr to a reference
-        // to the data held
-        let element = unsafe { &*array_element_ptr };
-        Some(element)
+        // Safety: see `as_bytes`; `&mut self` gives us exclusive access to write through
+        let layout = unsafe { Dst::<T>::layout_for(self.length()) };
+        Some(unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr() as *mut u8, layout.size()) })
     }
+}
+impl<T: Sized, A: Allocator> Drop for OwnedInstanceRef<T, A> {
+    fn drop(&mut self) {
+        // The allocation is always sized for the full capacity (`length()`), but only the
+        // `init_len` prefix holds live `T`s that need dropping.
+        let init_len = self.init_len;
+        let ptr = self.ptr;
 
-    pub fn get_mut(&mut self, i: u16) -> Option<&mut T> {
-        // We need the length to compute the proper layout and check validity
-        let length = self.length();
+        // Safety: the asserts in `alloc_with_header` already ensured this doesn't overflow isize
+        let layout = unsafe { Dst::<T>::layout_for(self.length()) };
+
+        // Fill it with garbage so any UAFs are more likely to combust
+        self.ptr = NonNull::new(std::ptr::from_raw_parts_mut(
+            NonNull::<()>::dangling().as_ptr(),
+            0,
+        ))
+        .unwrap();
+
+        // Drop the initialized elements
+        // Safety: `ptr` is the allocation's original fat pointer, still valid until we deallocate
+        let data_ptr = unsafe { std::ptr::addr_of_mut!((*ptr.as_ptr()).data) as *mut T };
+        for i in 0..init_len {
+            let elem_ptr = unsafe { data_ptr.add(usize::from(i)) };
+            let value = unsafe { std::ptr::read(elem_ptr) };
+            drop(value);
+        }
 
-        if i >= length {
-            // Index was out of bounds
+        // Safety: ptr/layout came from a matching `self.alloc.allocate(layout)` and we can't
+        // drop twice.
+        unsafe { self.alloc.deallocate(ptr.cast::<u8>(), layout) };
+    }
+}
+
+/// A borrowed, zero-copy view of an instance living inside an existing byte buffer (e.g. one
+/// read from disk or received over a socket), reconstructed without copying the buffer.
+///
+/// Unlike [`OwnedInstanceRef`], this never allocates or deallocates: it only reinterprets bytes
+/// it doesn't own, so there's nothing to free when it's dropped.
+pub struct InstanceView<'a, T> {
+    ptr: NonNull<Dst<T>>,
+    /// The header's `id`, already decoded according to whichever constructor built this view.
+    /// Read from here rather than re-reading `ptr`'s header bytes natively, since those bytes are
+    /// big-endian (not native-endian) for a view built via [`Self::from_wire_bytes`].
+    id: u32,
+    /// See [`Self::id`]; the same reasoning applies to `length`.
+    length: u16,
+    _marker: PhantomData<&'a Dst<T>>,
+}
+impl<'a, T: Sized + AnyBitPattern> InstanceView<'a, T> {
+    /// Reinterpret `bytes` as an instance's header and trailing array, without copying.
+    ///
+    /// This assumes `bytes` was produced on a machine with the same native endianness as this
+    /// one (the header's `id`/`length` are read as native-endian integers); for a buffer that
+    /// may have crossed machines, use [`Self::from_wire_bytes`] instead.
+    ///
+    /// Returns `None` if `bytes` is too short to contain a header, if its length doesn't exactly
+    /// match the header-declared `length`'s worth of trailing elements, if `T`'s alignment would
+    /// require padding between the header and the array (see [`OwnedInstanceRef::as_bytes`]) that
+    /// `bytes` can't be known to satisfy, or if `bytes`'s address isn't itself aligned for
+    /// `Dst<T>`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<InstanceView<'a, T>> {
+        if bytes.len() < std::mem::size_of::<u32>() + std::mem::size_of::<u16>() {
             return None;
         }
 
-        // From here on out, the index is valid
+        let id_bytes: [u8; 4] = bytes[0..4].try_into().unwrap();
+        let id = u32::from_ne_bytes(id_bytes);
+        let length_bytes: [u8; 2] = bytes[4..6].try_into().unwrap();
+        let length = u16::from_ne_bytes(length_bytes);
 
-        // Should not panic since we had to do the same logic to construct this instance
-        // in the first place
-        let (_array_layout, array_start_offset) = compute_array_layout_part::<T>(length).unwrap();
+        Self::from_parts(bytes, id, length)
+    }
 
-        let array_start_ptr: *mut u8 = unsafe { self.ptr.as_ptr().add(array_start_offset) };
-        let array_start_ptr: *mut T = array_start_ptr.cast::<T>();
+    /// Like [`Self::from_bytes`], but reads the header's `id`/`length` via explicit
+    /// big-endian wrapper types ([`U32Be`]/[`U16Be`]) instead of the host's native endianness, so
+    /// a buffer written on a different machine still parses correctly.
+    pub fn from_wire_bytes(bytes: &'a [u8]) -> Option<InstanceView<'a, T>> {
+        if bytes.len() < std::mem::size_of::<u32>() + std::mem::size_of::<u16>() {
+            return None;
+        }
 
-        // Safety: i must be valid since the constructor assured that all indices up to length
-        // are valid, and we have asserted that `i < length`
-        let array_element_ptr: *mut T = unsafe { array_start_ptr.add(usize::from(i)) };
+        let id_bytes: [u8; 4] = bytes[0..4].try_into().unwrap();
+        let id = U32Be(id_bytes).get();
+        let length_bytes: [u8; 2] = bytes[4..6].try_into().unwrap();
+        let length = U16Be(length_bytes).get();
 
-        // Safety: We are a mutable reference and so we are uniquely referenced and so we can return
-        // a unique reference to the data inside us.
-        let element = unsafe { &mut *array_element_ptr };
-        Some(element)
+        Self::from_parts(bytes, id, length)
+    }
+
+    /// Common tail of [`Self::from_bytes`]/[`Self::from_wire_bytes`]: `id`/`length` must already
+    /// be decoded (native- or wire-endian, depending on the caller), since [`InstanceView`]
+    /// records them as-decoded rather than re-deriving them from the raw header bytes later.
+    fn from_parts(bytes: &'a [u8], id: u32, length: u16) -> Option<InstanceView<'a, T>> {
+        if OwnedInstanceRef::<T>::has_header_padding() {
+            return None;
+        }
+
+        // Safety: `length` comes from the buffer itself, so it can't overflow isize unless the
+        // buffer itself already would have
+        let layout = unsafe { Dst::<T>::layout_for(length) };
+        if bytes.len() != layout.size() {
+            return None;
+        }
+
+        // The accessors below read through `*const Dst<T>` with ordinary (aligned) field/index
+        // reads, not `read_unaligned`, so `bytes` must actually satisfy `Dst<T>`'s alignment.
+        // A `&[u8]` (whether from a `Vec<u8>`, disk or a socket) is only guaranteed to be
+        // 1-aligned.
+        if !(bytes.as_ptr() as usize).is_multiple_of(layout.align()) {
+            return None;
+        }
+
+        let thin = NonNull::from(bytes).cast::<()>();
+        let fat: *const Dst<T> = std::ptr::from_raw_parts(thin.as_ptr(), usize::from(length));
+        // Safety:
+        // - `bytes` is exactly `layout.size()` bytes, matching a `Dst<T>` of this `length`
+        // - `T: AnyBitPattern` means every bit pattern in the trailing array is a valid `T`
+        // - no padding was introduced between the header and the array (checked above)
+        let ptr = NonNull::new(fat as *mut Dst<T>).unwrap();
+
+        Some(InstanceView {
+            ptr,
+            id,
+            length,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
     }
+
+    pub fn length(&self) -> u16 {
+        self.length
+    }
+
+    pub fn as_slice(&self) -> &'a [T] {
+        let length = usize::from(self.length());
+        // Safety: `from_parts` validated the buffer covers exactly `length` trailing `T`s, all
+        // of which are valid because `T: AnyBitPattern`
+        unsafe {
+            let data_ptr = std::ptr::addr_of!((*self.ptr.as_ptr()).data) as *const T;
+            std::slice::from_raw_parts(data_ptr, length)
+        }
+    }
+}
+
+/// Scope-guard for the in-progress fill loop in [`InstancePool::insert_in`], mirroring
+/// [`PartialInitGuard`]: if `default_elem_func` panics partway through, this guard's `Drop` runs
+/// instead of leaving `init_count` uninitialized-but-written elements un-dropped. Unlike
+/// `PartialInitGuard`, there's no allocation to free here — the bytes live in the pool's `buf`,
+/// whose length we only bump after every element is written, so a panic just leaves them as
+/// unreachable spare capacity for a later `insert` to overwrite.
+struct PoolFillGuard<T> {
+    data_ptr: *mut T,
+    init_count: u16,
 }
-impl<T: Sized> Drop for OwnedInstanceRef<T> {
+impl<T> Drop for PoolFillGuard<T> {
     fn drop(&mut self) {
-        let length = self.length();
-        let ptr = self.ptr.as_ptr();
+        for i in 0..self.init_count {
+            // Safety: indices below `init_count` were written by the caller before bumping it
+            let elem_ptr = unsafe { self.data_ptr.add(usize::from(i)) };
+            let value = unsafe { std::ptr::read(elem_ptr) };
+            drop(value);
+        }
+    }
+}
 
-        // Fill it with garbage so any UAFs are more likely to combust
-        self.ptr = NonNull::dangling();
+/// An opaque handle into an [`InstancePool`]. It only indexes the pool's side table of
+/// `(offset, length)` entries rather than pointing directly into the backing buffer, so it stays
+/// valid across a [`InstancePool::reserve`] or [`InstancePool::insert`] that reallocates the
+/// buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Handle(u32);
+
+/// The pool's side table entry for one instance: where it starts in `buf`, how many trailing
+/// elements it has (the same `length` that would otherwise live in the fat pointer's metadata),
+/// the `TypeId` it was inserted as (checked by [`InstancePool::get`]/[`InstancePool::get_mut`]/
+/// [`InstancePool::id`] so a caller can never reinterpret the bytes as the wrong `T`), and
+/// type-erased drop glue for those elements.
+struct PoolEntry {
+    offset: usize,
+    length: u16,
+    type_id: TypeId,
+    drop_fn: unsafe fn(*mut u8, u16),
+}
+
+/// Drop glue for a `Dst<T>`'s trailing elements, type-erased into a plain function pointer so
+/// [`InstancePool`] can drop instances of differing `T`; the `TypeId` each entry records only
+/// identifies `T` and can't drop anything by itself.
+///
+/// Safety: `base` must point at the start of a fully-initialized `Dst<T>` with `length` trailing
+/// elements, as established by [`InstancePool::insert`].
+unsafe fn drop_dst_elements<T>(base: *mut u8, length: u16) {
+    let fat: *mut Dst<T> =
+        std::ptr::from_raw_parts_mut(base as *mut (), usize::from(length));
+    // Safety: caller upholds that `data` is `length` fully-initialized elements
+    let data_ptr = unsafe { std::ptr::addr_of_mut!((*fat).data) as *mut T };
+    for i in 0..length {
+        unsafe { std::ptr::drop_in_place(data_ptr.add(usize::from(i))) };
+    }
+}
+
+/// A contiguous, growable heap for many instances, of potentially differing lengths and element
+/// types, packed one after another into a single backing buffer instead of one `alloc` call each.
+///
+/// Instances are addressed by opaque [`Handle`]s rather than pointers, so the pool is free to
+/// reallocate `buf` (in `reserve`/`insert`) without invalidating anything callers are holding onto.
+///
+/// `buf` is a raw allocation rather than a `Vec<u8, A>`: a `Vec<u8, _>`'s allocation is only ever
+/// guaranteed aligned to `align_of::<u8>() == 1`, but `insert` packs each instance by padding the
+/// *offset within the buffer* up to its alignment, which only places it at a properly aligned
+/// address if the buffer's own base pointer is aligned at least that far too. So `buf` tracks
+/// `align`, the strictest alignment of any instance inserted so far, and every reallocation
+/// allocates with that alignment (growing it, and copying/reallocating, whenever an instance
+/// needing a stricter one comes in).
+pub struct InstancePool<A: Allocator = Global> {
+    buf: NonNull<u8>,
+    len: usize,
+    cap: usize,
+    align: usize,
+    alloc: A,
+    entries: Vec<PoolEntry>,
+}
+impl InstancePool<Global> {
+    pub fn new() -> InstancePool<Global> {
+        Self::new_in(Global)
+    }
+}
+impl<A: Allocator> InstancePool<A> {
+    pub fn new_in(alloc: A) -> InstancePool<A> {
+        InstancePool {
+            buf: NonNull::dangling(),
+            len: 0,
+            cap: 0,
+            align: 1,
+            alloc,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Reserve space for at least `additional` more bytes without reallocating again. Any
+    /// outstanding [`Handle`]s stay valid: they only index `entries`, not `buf` directly.
+    pub fn reserve(&mut self, additional: usize) {
+        self.grow_to(self.len + additional, self.align);
+    }
+
+    /// Grow `buf` (if needed) so it has room for at least `required_len` bytes, with its base
+    /// pointer aligned to at least `required_align`. Copies the live `len` bytes over and
+    /// deallocates the old buffer; existing [`Handle`]s stay valid because they only index
+    /// `entries`, never `buf` directly.
+    fn grow_to(&mut self, required_len: usize, required_align: usize) {
+        let new_align = self.align.max(required_align);
+        if required_len <= self.cap && new_align == self.align {
+            return;
+        }
+
+        let new_cap = required_len.max(self.cap.saturating_mul(2));
+        let new_layout =
+            Layout::from_size_align(new_cap, new_align).expect("pool buffer layout overflow");
+
+        // Safety: `new_layout` has a non-zero size whenever we reach this point (required_len
+        // would have to be 0 and cap 0, which is already covered by the early return above)
+        let new_buf = match self.alloc.allocate(new_layout) {
+            Ok(ptr) => ptr.cast::<u8>(),
+            Err(_) => std::alloc::handle_alloc_error(new_layout),
+        };
+
+        if self.len > 0 {
+            // Safety: `new_buf` has room for at least `self.len` bytes, and `self.buf` is valid
+            // for reads of `self.len` bytes
+            unsafe { std::ptr::copy_nonoverlapping(self.buf.as_ptr(), new_buf.as_ptr(), self.len) };
+        }
+        if self.cap > 0 {
+            // Safety: `self.buf`/`self.cap`/`self.align` describe the allocation `self.alloc`
+            // handed back the last time `buf` was (re)allocated
+            let old_layout = Layout::from_size_align(self.cap, self.align).unwrap();
+            unsafe { self.alloc.deallocate(self.buf, old_layout) };
+        }
+
+        self.buf = new_buf;
+        self.cap = new_cap;
+        self.align = new_align;
+    }
+
+    /// Insert a new `length`-element instance of `T`, filled via `default_elem_func`, padding the
+    /// buffer up to `Dst<T>`'s alignment (at least the header's 4, or `T`'s if stricter) first. Returns a [`Handle`] to the new instance.
+    pub fn insert<T: Sized + 'static>(
+        &mut self,
+        id: u32,
+        length: u16,
+        default_elem_func: impl Fn(usize) -> T,
+    ) -> Handle {
+        assert!(
+            isize::try_from(length).is_ok(),
+            "Failed to fit length into isize"
+        );
+
+        // Safety: the assert above ensures the struct's size doesn't overflow isize
+        let layout = unsafe { Dst::<T>::layout_for(length) };
+
+        let padded_offset = self.len.next_multiple_of(layout.align());
+        let end = padded_offset
+            .checked_add(layout.size())
+            .expect("pool buffer offset overflow");
+
+        // Ensures both that `buf` has room for `end` bytes and that `buf`'s base pointer is
+        // aligned to at least `layout.align()`, so `padded_offset` is truly an aligned address.
+        self.grow_to(end, layout.align());
 
-        let layout_info = InstanceLayoutInfo::<T>::new(length).unwrap();
-        let layout = layout_info.layout;
-        let array_start = layout_info.array_start_offset;
+        // Safety: `grow_to` just ensured `buf` has room for `end` bytes from its (now
+        // sufficiently aligned) base, even though `self.len` doesn't cover them yet
+        let fat: *mut Dst<T> = unsafe {
+            let base = self.buf.as_ptr();
+            std::ptr::from_raw_parts_mut(base.add(padded_offset) as *mut (), usize::from(length))
+        };
+
+        // Safety: `fat` points into `buf`'s spare capacity, sized for exactly `length` trailing
+        // elements and properly aligned by `padded_offset`
+        unsafe {
+            std::ptr::addr_of_mut!((*fat).id).write(id);
+            std::ptr::addr_of_mut!((*fat).length).write(length);
+            std::ptr::addr_of_mut!((*fat)._reserved).write(0);
+        }
 
-        // Drop the elements
-        let array_ptr = unsafe { ptr.add(array_start) };
-        let array_ptr = array_ptr.cast::<T>();
+        // Safety: same as above; `data` is the trailing `length`-element array
+        let data_ptr = unsafe { std::ptr::addr_of_mut!((*fat).data) as *mut T };
+        let mut guard = PoolFillGuard {
+            data_ptr,
+            init_count: 0,
+        };
         for i in 0..length {
-            let elem_ptr = unsafe { array_ptr.add(usize::from(i)) };
-            let value = unsafe { std::ptr::read(elem_ptr) };
-            drop(value);
+            // Safety: index is within the `length`-element array `fat` was formed for
+            let elem_ptr = unsafe { data_ptr.add(usize::from(i)) };
+            let value = default_elem_func(usize::from(i));
+            unsafe { std::ptr::write(elem_ptr, value) };
+            guard.init_count += 1;
+        }
+        std::mem::forget(guard);
+
+        // Safety: every byte from `self.len` up to `end` is now either padding we don't care
+        // about or a fully-initialized header/element
+        self.len = end;
+
+        let handle = Handle(self.entries.len() as u32);
+        self.entries.push(PoolEntry {
+            offset: padded_offset,
+            length,
+            type_id: TypeId::of::<T>(),
+            drop_fn: drop_dst_elements::<T>,
+        });
+        handle
+    }
+
+    fn entry(&self, handle: Handle) -> &PoolEntry {
+        &self.entries[handle.0 as usize]
+    }
+
+    /// The `length` an instance was inserted with.
+    pub fn length(&self, handle: Handle) -> u16 {
+        self.entry(handle).length
+    }
+
+    /// The `id` an instance was inserted with. Returns `None` if `T` doesn't match the type the
+    /// instance at `handle` was actually [`insert`](Self::insert)ed as.
+    pub fn id<T: Sized + 'static>(&self, handle: Handle) -> Option<u32> {
+        let entry = self.entry(handle);
+        if entry.type_id != TypeId::of::<T>() {
+            return None;
+        }
+
+        // Safety: `offset` was chosen by `insert` to be aligned for a `Dst<T>` and to have a
+        // fully-initialized header at that point in `buf`, and the `type_id` check above confirms
+        // `T` matches what it was inserted as
+        Some(unsafe {
+            let thin = self.buf.as_ptr().add(entry.offset) as *const ();
+            let fat: *const Dst<T> = std::ptr::from_raw_parts(thin, usize::from(entry.length));
+            std::ptr::addr_of!((*fat).id).read()
+        })
+    }
+
+    /// Rebuild a transient view of this instance's elements. Returns `None` if `T` doesn't match
+    /// the type the instance at `handle` was actually [`insert`](Self::insert)ed as.
+    pub fn get<T: Sized + 'static>(&self, handle: Handle) -> Option<&[T]> {
+        let entry = self.entry(handle);
+        if entry.type_id != TypeId::of::<T>() {
+            return None;
         }
 
-        // Safety: The pointer should be valid
-        // and we can't drop twice.
-        unsafe { std::alloc::dealloc(ptr, layout) };
+        // Safety: `offset` was chosen by `insert` to be aligned for a `Dst<T>` with `entry.length`
+        // trailing elements, all of which were initialized before `insert` returned, and the
+        // `type_id` check above confirms `T` matches what it was inserted as
+        Some(unsafe {
+            let thin = self.buf.as_ptr().add(entry.offset) as *const ();
+            let fat: *const Dst<T> = std::ptr::from_raw_parts(thin, usize::from(entry.length));
+            let data_ptr = std::ptr::addr_of!((*fat).data) as *const T;
+            std::slice::from_raw_parts(data_ptr, usize::from(entry.length))
+        })
+    }
+
+    /// Mutable counterpart to [`Self::get`].
+    pub fn get_mut<T: Sized + 'static>(&mut self, handle: Handle) -> Option<&mut [T]> {
+        let entry = self.entry(handle);
+        if entry.type_id != TypeId::of::<T>() {
+            return None;
+        }
+        let offset = entry.offset;
+        let length = entry.length;
+        // Safety: see `get`; `&mut self` gives us exclusive access to write through
+        Some(unsafe {
+            let thin = self.buf.as_ptr().add(offset) as *mut ();
+            let fat: *mut Dst<T> = std::ptr::from_raw_parts_mut(thin, usize::from(length));
+            let data_ptr = std::ptr::addr_of_mut!((*fat).data) as *mut T;
+            std::slice::from_raw_parts_mut(data_ptr, usize::from(length))
+        })
+    }
+}
+impl Default for InstancePool<Global> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<A: Allocator> Drop for InstancePool<A> {
+    fn drop(&mut self) {
+        // Drop every live instance's elements before `buf` (and its allocation) goes away; each
+        // entry's `TypeId` can't name its element type statically, so this goes through the
+        // type-erased `drop_fn` captured at `insert` time instead of a generic loop.
+        for entry in &self.entries {
+            // Safety: `entry.offset`/`entry.length`/`entry.drop_fn` were all recorded together by
+            // `insert` for the same `T`, and describe a fully-initialized instance
+            unsafe { (entry.drop_fn)(self.buf.as_ptr().add(entry.offset), entry.length) };
+        }
+
+        if self.cap > 0 {
+            // Safety: `self.buf`/`self.cap`/`self.align` describe the allocation `self.alloc`
+            // handed back the last time `buf` was (re)allocated, and we only deallocate once
+            let layout = Layout::from_size_align(self.cap, self.align).unwrap();
+            unsafe { self.alloc.deallocate(self.buf, layout) };
+        }
+    }
+}
+
+/// Which variant of a [`FieldValue`] is currently meaningful at a given slot, the same way a
+/// typed memory place pairs a scalar value with its type before reading it. [`TypedInstanceRef`]
+/// records one of these per element and checks it before every read, so a caller can never
+/// observe a union field under any variant but the one it was last written as.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FieldKind {
+    Long,
+    Int,
+    Float,
+    Double,
+    Byte,
+    Reference,
+    Invalid,
+}
+
+/// Declares a checked getter/setter pair for one [`FieldKind`]/[`FieldValue`] variant on
+/// [`TypedInstanceRef`]. The getter refuses to read the union unless the slot's recorded kind
+/// matches, and the setter updates the recorded kind alongside the value, so the two can never
+/// drift apart.
+macro_rules! typed_field_accessor {
+    ($get:ident, $set:ident, $ty:ty, $kind:ident, $variant:ident) => {
+        pub fn $get(&self, i: u16) -> Option<$ty> {
+            if self.kind(i)? != FieldKind::$kind {
+                return None;
+            }
+
+            // Safety: `self.kind(i) == Some(FieldKind::$kind)` means this slot was last written
+            // through `Self::$set`, so `$variant` is its active union field
+            Some(unsafe { self.inner.get(i)?.$variant })
+        }
+
+        pub fn $set(&mut self, i: u16, value: $ty) -> Option<()> {
+            *self.inner.get_mut(i)? = FieldValue { $variant: value };
+            self.kinds[usize::from(i)] = FieldKind::$kind;
+            Some(())
+        }
+    };
+}
+
+/// An instance whose fields are read and written through checked, kind-tagged accessors instead
+/// of directly through the [`FieldValue`] union, so reading a field under the wrong variant
+/// returns `None` instead of being undefined behavior.
+pub struct TypedInstanceRef<A: Allocator = Global> {
+    inner: OwnedInstanceRef<FieldValue, A>,
+    kinds: Vec<FieldKind>,
+}
+impl TypedInstanceRef<Global> {
+    pub fn new(id: u32, length: u16) -> Result<TypedInstanceRef<Global>, AllocError> {
+        Self::new_in(id, length, Global)
+    }
+}
+impl<A: Allocator> TypedInstanceRef<A> {
+    pub fn new_in(id: u32, length: u16, alloc: A) -> Result<TypedInstanceRef<A>, AllocError> {
+        let inner = OwnedInstanceRef::new_in(id, length, |_| FieldValue::default(), alloc)?;
+        let kinds = vec![FieldKind::Invalid; usize::from(length)];
+
+        Ok(TypedInstanceRef { inner, kinds })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    pub fn length(&self) -> u16 {
+        self.inner.length()
     }
+
+    /// Which variant, if any, the slot at `i` currently holds. `None` if `i` is out of bounds.
+    pub fn kind(&self, i: u16) -> Option<FieldKind> {
+        self.kinds.get(usize::from(i)).copied()
+    }
+
+    typed_field_accessor!(get_long, set_long, i64, Long, long);
+    typed_field_accessor!(get_int, set_int, i32, Int, int);
+    typed_field_accessor!(get_float, set_float, f32, Float, float);
+    typed_field_accessor!(get_double, set_double, f64, Double, double);
+    typed_field_accessor!(get_byte, set_byte, i8, Byte, byte);
+    typed_field_accessor!(get_reference, set_reference, u32, Reference, reference);
 }
 
 fn main() {
-    // DstWrapper would be in a module so it couldn't be constructed as a struct literal
-    // and could only go through new
-    let mut val =
-        OwnedInstanceRef::<FieldValue>::new(5, 4, |i| FieldValue { invalid: () }).unwrap();
+    let mut val = TypedInstanceRef::new(5, 4).unwrap();
     let id = val.id();
     println!("Id: {}", id);
     let length = val.length();
     println!("Length: {}", length);
 
     for i in 0..length {
-        let value = val.get_mut(i).unwrap();
-        value.int = i32::from(i);
-        println!("Value at {} is {}", i, unsafe { value.int });
+        val.set_int(i, i32::from(i)).unwrap();
+        println!("Value at {} is {}", i, val.get_int(i).unwrap());
     }
 
     for i in 0..length {
-        let value = val.get_mut(i).unwrap();
-        unsafe { value.int *= 2 };
+        let value = val.get_int(i).unwrap();
+        val.set_int(i, value * 2).unwrap();
+    }
+
+    let data: Vec<i32> = (0..length).map(|i| val.get_int(i).unwrap()).collect();
+    println!("Data: {:?}", data);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct DropCounter<'a>(&'a AtomicUsize);
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn new_drops_already_initialized_elements_on_panic() {
+        let drops = AtomicUsize::new(0);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            OwnedInstanceRef::<DropCounter>::new(1, 5, |i| {
+                if i == 3 {
+                    panic!("boom");
+                }
+                DropCounter(&drops)
+            })
+        }));
+
+        assert!(result.is_err());
+        // Elements 0, 1, 2 were written (and handed to the guard) before the panic on index 3;
+        // they should each be dropped exactly once, with nothing leaked and nothing double-dropped.
+        assert_eq!(drops.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn as_bytes_succeeds_for_field_value() {
+        let instance =
+            OwnedInstanceRef::<FieldValue>::new(1, 3, |_| FieldValue::default()).unwrap();
+        assert!(instance.as_bytes().is_some());
+    }
+
+    #[test]
+    fn instance_view_rejects_misaligned_buffer() {
+        // `FieldValue` has alignment 8; build a header-and-elements buffer at an address that
+        // can't possibly be 8-aligned, by taking a sub-slice that starts 1 byte in.
+        let instance =
+            OwnedInstanceRef::<FieldValue>::new(1, 2, |_| FieldValue::default()).unwrap();
+        let bytes = instance.as_bytes().unwrap();
+
+        // 8-byte header plus 2 * 8-byte elements, one byte past an 8-aligned base
+        #[repr(align(8))]
+        struct Aligned([u8; 25]);
+        let mut misaligned = Aligned([0u8; 25]);
+        misaligned.0[1..].copy_from_slice(bytes);
+
+        assert!(InstanceView::<FieldValue>::from_bytes(&misaligned.0[1..]).is_none());
+    }
+
+    #[test]
+    fn pool_get_handles_high_alignment_after_low_alignment_insert() {
+        #[repr(align(64))]
+        #[derive(Clone, Copy, Default)]
+        struct Over(u8);
+
+        let mut pool = InstancePool::new();
+        let low = pool.insert::<u8>(1, 1, |_| 0u8);
+        let high = pool.insert::<Over>(2, 1, |_| Over(7));
+
+        assert_eq!(pool.get::<u8>(low).unwrap(), &[0u8]);
+        assert_eq!(pool.get::<Over>(high).unwrap()[0].0, 7);
+    }
+
+    #[test]
+    fn pool_get_rejects_mismatched_type() {
+        let mut pool = InstancePool::new();
+        let handle = pool.insert::<u8>(1, 1, |_| 5u8);
+
+        assert!(pool.get::<u16>(handle).is_none());
+        assert!(pool.id::<u16>(handle).is_none());
+        assert_eq!(pool.id::<u8>(handle), Some(1));
     }
 
-    let data = val.as_slice();
-    println!(
-        "Data: {:?}",
-        data.iter().map(|x| unsafe { x.int }).collect::<Vec<_>>()
-    );
+    #[test]
+    fn from_wire_bytes_round_trips_big_endian_header() {
+        // `Dst<FieldValue>`'s header is 8 bytes (id/length/reserved) aligned to 8; build one by
+        // hand, with `id`/`length` written big-endian as if received from another machine.
+        #[repr(align(8))]
+        struct Aligned([u8; 16]);
+
+        let id = 0x0102_0304u32;
+        let length: u16 = 1;
+
+        let mut buf = Aligned([0u8; 16]);
+        buf.0[0..4].copy_from_slice(&id.to_be_bytes());
+        buf.0[4..6].copy_from_slice(&length.to_be_bytes());
+
+        let view = InstanceView::<FieldValue>::from_wire_bytes(&buf.0).unwrap();
+        assert_eq!(view.id(), id);
+        assert_eq!(view.length(), length);
+    }
 }